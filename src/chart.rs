@@ -0,0 +1,180 @@
+// chart renders one or more buoy metrics over time from a TOML config file, either to the
+// terminal with textplots-rs or, when the config gives an output_dir, to image files with
+// plotters.
+
+use crate::{BuoyCollection, BuoyDatum};
+use plotters::prelude::*;
+use rgb::RGB8;
+use serde::Deserialize;
+use std::{error::Error, fs};
+use textplots::{Chart as TextChart, ColorPlot, Shape};
+
+// PALETTE gives each metric in a chart a distinct, stable color.
+const PALETTE: [RGB8; 6] = [
+    RGB8 { r: 255, g: 0, b: 0 },
+    RGB8 { r: 0, g: 128, b: 255 },
+    RGB8 { r: 0, g: 200, b: 0 },
+    RGB8 { r: 255, g: 165, b: 0 },
+    RGB8 { r: 160, g: 0, b: 200 },
+    RGB8 { r: 0, g: 200, b: 200 },
+];
+
+// Metric names one BuoyDatum field that can be plotted.
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Metric {
+    SignificantWaveHeight,
+    MeanWavePeriod,
+    MeanWaveDirection,
+    WavePower,
+    PeakPeriod,
+    EnergyPeriod,
+}
+
+impl Metric {
+    fn value(self, d: &BuoyDatum) -> f32 {
+        match self {
+            Metric::SignificantWaveHeight => d.significant_wave_height,
+            Metric::MeanWavePeriod => d.mean_wave_period,
+            Metric::MeanWaveDirection => d.mean_wave_direction,
+            Metric::WavePower => d.wave_power,
+            Metric::PeakPeriod => d.peak_period,
+            Metric::EnergyPeriod => d.energy_period,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Metric::SignificantWaveHeight => "significant_wave_height",
+            Metric::MeanWavePeriod => "mean_wave_period",
+            Metric::MeanWaveDirection => "mean_wave_direction",
+            Metric::WavePower => "wave_power",
+            Metric::PeakPeriod => "peak_period",
+            Metric::EnergyPeriod => "energy_period",
+        }
+    }
+}
+
+// ChartSpec describes which stations and metrics to plot, up to what time. Each listed station
+// gets its own chart with one line per metric; stations are not combined into one chart.
+#[derive(Deserialize)]
+struct ChartSpec {
+    title: String,
+    station_ids: Vec<String>,
+    max_time: String,
+    metrics: Vec<Metric>,
+}
+
+// ChartsConfig is the top-level shape of the TOML chart config file.
+#[derive(Deserialize)]
+struct ChartsConfig {
+    #[serde(default)]
+    output_dir: Option<String>,
+    #[serde(default = "default_dim")]
+    width: u32,
+    #[serde(default = "default_dim")]
+    height: u32,
+    charts: Vec<ChartSpec>,
+}
+
+fn default_dim() -> u32 {
+    800
+}
+
+// render_charts reads config_path as a ChartsConfig and renders every chart it describes.
+pub(crate) fn render_charts(coll: &BuoyCollection, config_path: &str) -> Result<(), Box<dyn Error>> {
+    let config: ChartsConfig = toml::from_str(&fs::read_to_string(config_path)?)?;
+    if let Some(dir) = &config.output_dir {
+        fs::create_dir_all(dir)?;
+    }
+
+    for spec in &config.charts {
+        let max_time = bson::DateTime::parse_rfc3339_str(&spec.max_time)?;
+        for station_id in &spec.station_ids {
+            let data: Vec<BuoyDatum> = coll
+                .find_buoy(station_id)?
+                .into_iter()
+                .filter(|d| d.time <= max_time)
+                .collect();
+            if data.is_empty() {
+                continue;
+            }
+
+            match &config.output_dir {
+                Some(dir) => save_chart_image(dir, spec, station_id, &data, config.width, config.height)?,
+                None => print_chart(spec, station_id, &data),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// print_chart renders one chart's series to the terminal with textplots-rs.
+fn print_chart(spec: &ChartSpec, station_id: &str, data: &[BuoyDatum]) {
+    println!("--- {} ({}) ---", spec.title, station_id);
+
+    let mut plot = TextChart::new(120, 60, 0.0, data.len() as f32);
+    for (i, metric) in spec.metrics.iter().enumerate() {
+        let series: Vec<(f32, f32)> = data
+            .iter()
+            .enumerate()
+            .map(|(idx, d)| (idx as f32, metric.value(d)))
+            .collect();
+        plot.linecolorplot(&Shape::Lines(&series), PALETTE[i % PALETTE.len()]);
+    }
+    plot.display();
+}
+
+// save_chart_image renders one chart's series to a PNG file under dir with plotters.
+fn save_chart_image(
+    dir: &str,
+    spec: &ChartSpec,
+    station_id: &str,
+    data: &[BuoyDatum],
+    width: u32,
+    height: u32,
+) -> Result<(), Box<dyn Error>> {
+    let path = format!("{}/{}_{}.png", dir, slug(&spec.title), slug(station_id));
+    let root = BitMapBackend::new(&path, (width, height)).into_drawing_area();
+    root.fill(&WHITE)?;
+
+    let (mut min_y, mut max_y) = (f32::MAX, f32::MIN);
+    for metric in &spec.metrics {
+        for d in data {
+            let v = metric.value(d);
+            min_y = min_y.min(v);
+            max_y = max_y.max(v);
+        }
+    }
+
+    let mut cc = ChartBuilder::on(&root)
+        .caption(format!("{} - {}", spec.title, station_id), ("sans-serif", 24))
+        .margin(20)
+        .x_label_area_size(30)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0f32..data.len() as f32, min_y..max_y)?;
+    cc.configure_mesh().draw()?;
+
+    for (i, metric) in spec.metrics.iter().enumerate() {
+        let color = PALETTE[i % PALETTE.len()];
+        let rgb = RGBColor(color.r, color.g, color.b);
+        cc.draw_series(LineSeries::new(
+            data.iter().enumerate().map(|(idx, d)| (idx as f32, metric.value(d))),
+            &rgb,
+        ))?
+        .label(metric.label())
+        .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], rgb));
+    }
+    cc.configure_series_labels().draw()?;
+    root.present()?;
+
+    Ok(())
+}
+
+// slug turns an arbitrary string into a filesystem-safe, lowercase fragment.
+fn slug(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
@@ -0,0 +1,21 @@
+// BuoyError is used by BuoyCollection's own methods; callers above it (http, chart, influx,
+// main) still propagate it as Box<dyn Error>, relying on the blanket From impl. MissingField
+// only carries `column`, not a row number: it's only ever raised for a missing header column,
+// which has no row to report.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum BuoyError {
+    #[error("reading csv: {0}")]
+    Csv(#[from] csv::Error),
+
+    #[error("parsing time: {0}")]
+    ParseTime(#[from] bson::datetime::Error),
+
+    #[error("mongo error: {0}")]
+    Mongo(#[from] mongodb::error::Error),
+
+    #[error("missing required column {column:?} in header")]
+    MissingField { column: String },
+}
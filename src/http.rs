@@ -0,0 +1,161 @@
+// Routing here is a plain match on (Method, path) rather than a router crate, since the route
+// set is small and unlikely to grow past a handful of /buoys paths; handlers return a Response
+// directly instead of a Result so a handler-level error becomes a JSON error body, not a
+// dropped connection.
+
+use crate::{BuoyCollection, RowPolicy};
+use serde::Deserialize;
+use std::{collections::HashMap, error::Error};
+use tiny_http::{Method, Response, Server};
+
+// DEFAULT_MAX_ENTRIES bounds a /range page when the caller doesn't supply max_entries.
+const DEFAULT_MAX_ENTRIES: usize = 100;
+
+// LoadRequest is the JSON body accepted by POST /load. skip_invalid opts into skip-and-log
+// handling of malformed CSV rows instead of failing the whole load.
+#[derive(Deserialize)]
+struct LoadRequest {
+    path: String,
+    #[serde(default)]
+    skip_invalid: bool,
+}
+
+// serve binds addr and answers requests against coll until the process is killed. Each request
+// is handled on the calling thread; BuoyCollection is cheap to clone, so every request gets its
+// own handle to the shared mongodb client.
+pub(crate) fn serve(coll: BuoyCollection, addr: &str) -> Result<(), Box<dyn Error>> {
+    let server = Server::http(addr).map_err(|e| format!("binding {}: {}", addr, e))?;
+    println!("listening on {}", addr);
+
+    for mut request in server.incoming_requests() {
+        let coll = coll.clone();
+        let (path, query) = match request.url().split_once('?') {
+            Some((path, query)) => (path.to_string(), query.to_string()),
+            None => (request.url().to_string(), String::new()),
+        };
+        let response = match (request.method().clone(), path.as_str()) {
+            (Method::Get, "/buoys") => handle_list_buoys(&coll),
+            (Method::Get, p) if p.starts_with("/buoys/") && p.ends_with("/range") => {
+                handle_query_range(&coll, &p["/buoys/".len()..p.len() - "/range".len()], &query)
+            }
+            (Method::Get, p) if p.starts_with("/buoys/") => {
+                handle_get_buoy(&coll, &p["/buoys/".len()..])
+            }
+            (Method::Delete, p) if p.starts_with("/buoys/") => {
+                handle_delete_buoy(&coll, &p["/buoys/".len()..])
+            }
+            (Method::Post, "/load") => handle_load(&coll, &mut request),
+            _ => json_response(404, &serde_json::json!({ "error": "not found" })),
+        };
+        // A single client going away mid-response shouldn't take the whole server down with it.
+        if let Err(e) = request.respond(response) {
+            eprintln!("failed to respond to request: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_list_buoys(coll: &BuoyCollection) -> Response<std::io::Cursor<Vec<u8>>> {
+    match coll.station_ids() {
+        Ok(ids) => json_response(200, &ids),
+        Err(e) => json_response(500, &serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+fn handle_get_buoy(coll: &BuoyCollection, station_id: &str) -> Response<std::io::Cursor<Vec<u8>>> {
+    match coll.find_buoy(station_id) {
+        Ok(data) => json_response(200, &data),
+        Err(e) => json_response(500, &serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+// handle_query_range answers GET /buoys/{station_id}/range?start=<rfc3339>&end=<rfc3339>
+// [&max_entries=<n>][&marker=<rfc3339>], wrapping BuoyCollection::query so callers can page
+// through a time range instead of pulling it all into memory at once.
+fn handle_query_range(
+    coll: &BuoyCollection,
+    station_id: &str,
+    query: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let params = parse_query(query);
+
+    let start = match params.get("start").and_then(|s| bson::DateTime::parse_rfc3339_str(s).ok()) {
+        Some(t) => t,
+        None => return json_response(400, &serde_json::json!({ "error": "missing or invalid start" })),
+    };
+    let end = match params.get("end").and_then(|s| bson::DateTime::parse_rfc3339_str(s).ok()) {
+        Some(t) => t,
+        None => return json_response(400, &serde_json::json!({ "error": "missing or invalid end" })),
+    };
+    let max_entries = match params.get("max_entries").map(|s| s.parse::<usize>()) {
+        // mongodb's FindOptions::limit treats 0 as "no limit", so a caller-supplied 0 would
+        // silently turn this bounded page into an unbounded dump of the whole range.
+        Some(Ok(0)) => {
+            return json_response(400, &serde_json::json!({ "error": "max_entries must be greater than 0" }))
+        }
+        Some(Ok(n)) => n,
+        Some(Err(_)) => return json_response(400, &serde_json::json!({ "error": "invalid max_entries" })),
+        None => DEFAULT_MAX_ENTRIES,
+    };
+    let marker = match params.get("marker") {
+        Some(s) => match bson::DateTime::parse_rfc3339_str(s) {
+            Ok(t) => Some(t),
+            Err(_) => return json_response(400, &serde_json::json!({ "error": "invalid marker" })),
+        },
+        None => None,
+    };
+
+    match coll.query(station_id, start, end, max_entries, marker) {
+        Ok((data, next_marker)) => json_response(200, &serde_json::json!({ "data": data, "marker": next_marker })),
+        Err(e) => json_response(500, &serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+// parse_query splits a `key=value&key=value` query string into a lookup map.
+fn parse_query(query: &str) -> HashMap<&str, &str> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .collect()
+}
+
+fn handle_delete_buoy(
+    coll: &BuoyCollection,
+    station_id: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    match coll.delete_buoy(station_id) {
+        Ok(()) => json_response(200, &serde_json::json!({ "deleted": station_id })),
+        Err(e) => json_response(500, &serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+fn handle_load(
+    coll: &BuoyCollection,
+    request: &mut tiny_http::Request,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let body: LoadRequest = match serde_json::from_reader(request.as_reader()) {
+        Ok(b) => b,
+        Err(e) => return json_response(400, &serde_json::json!({ "error": e.to_string() })),
+    };
+    let policy = if body.skip_invalid {
+        RowPolicy::SkipAndLog
+    } else {
+        RowPolicy::FailFast
+    };
+    match coll.load_csv(&body.path, policy) {
+        Ok(()) => json_response(200, &serde_json::json!({ "loaded": body.path })),
+        Err(e) => json_response(500, &serde_json::json!({ "error": e.to_string() })),
+    }
+}
+
+// json_response serializes body to JSON and wraps it with the given status code.
+fn json_response<T: serde::Serialize>(
+    status: u16,
+    body: &T,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let bytes = serde_json::to_vec(body).unwrap_or_default();
+    Response::from_data(bytes).with_status_code(status).with_header(
+        tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+    )
+}
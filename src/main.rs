@@ -3,15 +3,64 @@ use mongodb::{
     options::{CreateCollectionOptions, FindOptions, TimeseriesGranularity, TimeseriesOptions},
     sync::Client,
 };
-use rgb::RGB8;
 use serde::{Deserialize, Serialize};
-use std::{env, error::Error};
-use textplots::{Chart, ColorPlot, Shape};
+use std::{env, error::Error, fs, thread};
+
+mod chart;
+mod error;
+mod http;
+mod influx;
+
+use error::BuoyError;
+
+// MIN_CHUNK is the smallest batch size load_csv will ever use, even for tiny
+// files, so a handful of records doesn't get split across several
+// insert_many calls.
+const MIN_CHUNK: usize = 100;
+
+// EST_RECORD_BYTES is a rough estimate of a CSV record's serialized size,
+// used to size batches from the file length alone without a full parse.
+const EST_RECORD_BYTES: u64 = 64;
+
+// CHUNKS_PER_THREAD is the target number of batches per available thread
+// when sizing batches for a large file. load_csv still issues its
+// insert_many calls one after another on the calling thread.
+const CHUNKS_PER_THREAD: usize = 4;
 
 // BuoyDatum represents a single, reported buoy measurement.
-#[derive(Deserialize, Serialize)]
-struct BuoyDatum {
-    time: bson::DateTime,
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct BuoyDatum {
+    pub(crate) time: bson::DateTime,
+    pub(crate) longitude: f32,
+    pub(crate) latitude: f32,
+    pub(crate) station_id: String,
+    pub(crate) significant_wave_height: f32,
+    pub(crate) mean_wave_period: f32,
+    pub(crate) mean_wave_direction: f32,
+    pub(crate) wave_power: f32,
+    pub(crate) peak_period: f32,
+    pub(crate) energy_period: f32,
+}
+
+// CSV_COLUMNS lists the columns load_csv requires the header row to contain, in no particular
+// order.
+const CSV_COLUMNS: [&str; 10] = [
+    "time",
+    "longitude",
+    "latitude",
+    "station_id",
+    "significant_wave_height",
+    "mean_wave_period",
+    "mean_wave_direction",
+    "wave_power",
+    "peak_period",
+    "energy_period",
+];
+
+// CsvRecord mirrors one row of the CSV input, mapped by header name rather than position.
+#[derive(Deserialize)]
+struct CsvRecord {
+    time: String,
     longitude: f32,
     latitude: f32,
     station_id: String,
@@ -23,8 +72,40 @@ struct BuoyDatum {
     energy_period: f32,
 }
 
-// BuoyCollection is a time-series collection with buoy data.
-struct BuoyCollection {
+impl TryFrom<CsvRecord> for BuoyDatum {
+    type Error = BuoyError;
+
+    fn try_from(r: CsvRecord) -> Result<BuoyDatum, BuoyError> {
+        Ok(BuoyDatum {
+            time: bson::DateTime::parse_rfc3339_str(&r.time)?,
+            longitude: r.longitude,
+            latitude: r.latitude,
+            station_id: r.station_id,
+            significant_wave_height: r.significant_wave_height,
+            mean_wave_period: r.mean_wave_period,
+            mean_wave_direction: r.mean_wave_direction,
+            wave_power: r.wave_power,
+            peak_period: r.peak_period,
+            energy_period: r.energy_period,
+        })
+    }
+}
+
+// RowPolicy controls how load_csv reacts to a malformed CSV row.
+#[derive(Clone, Copy)]
+pub(crate) enum RowPolicy {
+    // FailFast aborts the whole load on the first bad row.
+    FailFast,
+
+    // SkipAndLog skips bad rows (logging them to stderr) and keeps going.
+    SkipAndLog,
+}
+
+// BuoyCollection is a time-series collection with buoy data. It is cheap to clone, since the
+// underlying mongodb client and collection handles are themselves reference-counted, so each
+// HTTP request can get its own handle.
+#[derive(Clone)]
+pub(crate) struct BuoyCollection {
     // dbg turns on extra logging through stdout.
     dbg: bool,
 
@@ -33,7 +114,12 @@ struct BuoyCollection {
 }
 
 impl BuoyCollection {
-    fn new(dbg: bool) -> Result<BuoyCollection, Box<dyn Error>> {
+    // new connects to the configured mongo instance and returns a handle to the buoy
+    // collection. If fresh is true, any existing collection of the same name is dropped and
+    // recreated empty; this is only appropriate for the one-shot demo path. The `serve` and
+    // `export-influx` subcommands read previously-ingested data and must pass fresh: false so
+    // they attach to it instead of destroying it.
+    pub(crate) fn new(dbg: bool, fresh: bool) -> Result<BuoyCollection, BuoyError> {
         // Connect to server.
         let client = Client::with_uri_str(
             env::var("MONGODB_URI").unwrap_or("mongodb://localhost:27017/".to_string()),
@@ -50,25 +136,36 @@ impl BuoyCollection {
             env::var("COLLECTION").unwrap_or("coll".to_string()),
             client.database(env::var("DATABASE").unwrap_or("db".to_string()).as_str()),
         );
-        let coll = db.collection::<BuoyDatum>(coll_name.as_str());
-        coll.drop(None)?;
-        if dbg {
-            println!("dropped collection");
+
+        if fresh {
+            db.collection::<BuoyDatum>(coll_name.as_str()).drop(None)?;
+            if dbg {
+                println!("dropped collection");
+            }
         }
-        db.create_collection(
-            coll_name.as_str(),
-            CreateCollectionOptions::builder()
-                .timeseries(
-                    TimeseriesOptions::builder()
-                        .time_field("time".to_string())
-                        .meta_field(Some("station_id".to_string()))
-                        .granularity(Some(TimeseriesGranularity::Minutes))
-                        .build(),
-                )
-                .build(),
-        )?;
-        if dbg {
-            println!("created buoy collection {}", coll_name);
+
+        let exists = db
+            .list_collection_names(None)?
+            .iter()
+            .any(|n| n == &coll_name);
+        if !exists {
+            db.create_collection(
+                coll_name.as_str(),
+                CreateCollectionOptions::builder()
+                    .timeseries(
+                        TimeseriesOptions::builder()
+                            .time_field("time".to_string())
+                            .meta_field(Some("station_id".to_string()))
+                            .granularity(Some(TimeseriesGranularity::Minutes))
+                            .build(),
+                    )
+                    .build(),
+            )?;
+            if dbg {
+                println!("created buoy collection {}", coll_name);
+            }
+        } else if dbg {
+            println!("attaching to existing buoy collection {}", coll_name);
         }
 
         Ok(BuoyCollection {
@@ -78,44 +175,53 @@ impl BuoyCollection {
     }
 
     // load_csv will populate the collection with buoy data from the file referenced by the file
-    // path.
-    fn load_csv(&self, fp: &str) -> Result<(), Box<dyn Error>> {
+    // path, batching records into insert_many calls instead of inserting one at a time. policy
+    // decides whether a malformed row aborts the whole load or is skipped and logged.
+    pub(crate) fn load_csv(&self, fp: &str, policy: RowPolicy) -> Result<(), BuoyError> {
         // Build the CSV reader and iterate over each record.
         if self.dbg {
             println!("loading from {} into buoy collection...", fp);
         }
 
+        let file_len = fs::metadata(fp).map_err(csv::Error::from)?.len();
+        let chunk_size = chunk_size_for(file_len);
+        if self.dbg {
+            println!("using chunk size {}", chunk_size);
+        }
+
         let mut rdr = csv::Reader::from_path(fp)?;
-        for result in rdr.records() {
-            let record = result?;
-
-            // Pray that the CSV is well-formed...
-            let time_str = record.get(0).unwrap_or_default();
-            let longitude_str = record.get(1).unwrap_or_default();
-            let latitude_str = record.get(2).unwrap_or_default();
-            let station_id_str = record.get(3).unwrap_or_default();
-            let swh_str = record.get(4).unwrap_or_default();
-            let mwp_str = record.get(5).unwrap_or_default();
-            let mwd_str = record.get(6).unwrap_or_default();
-            let wave_power_str = record.get(7).unwrap_or_default();
-            let peak_period_str = record.get(8).unwrap_or_default();
-            let energy_period_str = record.get(9).unwrap_or_default();
-
-            self.coll.insert_one(
-                BuoyDatum {
-                    time: bson::DateTime::parse_rfc3339_str(time_str)?,
-                    longitude: longitude_str.parse::<f32>().unwrap(),
-                    latitude: latitude_str.parse::<f32>().unwrap(),
-                    station_id: station_id_str.to_string(),
-                    significant_wave_height: swh_str.parse::<f32>().unwrap(),
-                    mean_wave_period: mwp_str.parse::<f32>().unwrap(),
-                    mean_wave_direction: mwd_str.parse::<f32>().unwrap(),
-                    wave_power: wave_power_str.parse::<f32>().unwrap(),
-                    peak_period: peak_period_str.parse::<f32>().unwrap(),
-                    energy_period: energy_period_str.parse::<f32>().unwrap(),
+        let headers = rdr.headers()?.clone();
+        for column in CSV_COLUMNS {
+            if !headers.iter().any(|h| h == column) {
+                return Err(BuoyError::MissingField {
+                    column: column.to_string(),
+                });
+            }
+        }
+
+        let mut chunk = Vec::with_capacity(chunk_size);
+        for (row, result) in rdr.deserialize::<CsvRecord>().enumerate() {
+            let datum = match result.map_err(BuoyError::from).and_then(BuoyDatum::try_from) {
+                Ok(datum) => datum,
+                Err(e) => match policy {
+                    RowPolicy::FailFast => return Err(e),
+                    RowPolicy::SkipAndLog => {
+                        // rdr.deserialize() only enumerates data rows, so row 0 is the first
+                        // row after the header; add 2 (1 for the header line, 1 to make it
+                        // 1-indexed) to get the real line number in fp.
+                        eprintln!("skipping malformed row at {}:{}: {}", fp, row + 2, e);
+                        continue;
+                    }
                 },
-                None,
-            )?;
+            };
+
+            chunk.push(datum);
+            if chunk.len() == chunk_size {
+                self.coll.insert_many(chunk.drain(..), None)?;
+            }
+        }
+        if !chunk.is_empty() {
+            self.coll.insert_many(chunk.drain(..), None)?;
         }
         if self.dbg {
             println!("finished loading");
@@ -125,7 +231,7 @@ impl BuoyCollection {
     }
 
     // delete_buoy will delete all data associated with the supplied buoy from the collection.
-    fn delete_buoy(&self, buoy: &str) -> Result<(), Box<dyn Error>> {
+    pub(crate) fn delete_buoy(&self, buoy: &str) -> Result<(), BuoyError> {
         if self.dbg {
             println!("deleting all {} data from buoy collection...", buoy);
         }
@@ -136,48 +242,191 @@ impl BuoyCollection {
         Ok(())
     }
 
-    // list_buoys will print all buoys with data in the collection to stdout.
-    fn list_buoys(&self) -> Result<(), Box<dyn Error>> {
+    // station_ids returns the distinct buoys with data in the collection.
+    pub(crate) fn station_ids(&self) -> Result<Vec<String>, BuoyError> {
         let buoys = self.coll.distinct("station_id", doc! {}, None)?;
-        println!("{:?}", buoys);
+        Ok(buoys
+            .into_iter()
+            .filter_map(|b| b.as_str().map(str::to_string))
+            .collect())
+    }
+
+    // list_buoys will print all buoys with data in the collection to stdout.
+    fn list_buoys(&self) -> Result<(), BuoyError> {
+        println!("{:?}", self.station_ids()?);
         Ok(())
     }
 
-    // draw_buoy will visualize the statistics of a buoy with textplots-rs.
-    fn draw_buoy(&self, buoy: &str) -> Result<(), Box<dyn Error>> {
+    // find_buoy returns every BuoyDatum recorded for the given station, sorted ascending by
+    // time.
+    pub(crate) fn find_buoy(&self, buoy: &str) -> Result<Vec<BuoyDatum>, BuoyError> {
+        self.find_all(Some(buoy))
+    }
+
+    // find_all returns every BuoyDatum in the collection, optionally restricted to one station,
+    // sorted ascending by time.
+    pub(crate) fn find_all(&self, station_id: Option<&str>) -> Result<Vec<BuoyDatum>, BuoyError> {
+        let filter = match station_id {
+            Some(id) => doc! { "station_id": id },
+            None => doc! {},
+        };
         let cur = self.coll.find(
-            doc! { "station_id": buoy },
+            filter,
             FindOptions::builder().sort(doc! { "time": 1 }).build(),
         )?;
+        cur.map(|d| d.map_err(BuoyError::from)).collect()
+    }
 
-        let mut swh = Vec::new();
-        let mut i = -100.0;
-        for buoy_datum in cur {
-            let buoy_datum = buoy_datum?;
-            swh.push((i, buoy_datum.significant_wave_height));
-            i += 1.0;
+    // query fetches up to max_entries BuoyData for the given station within [start, end],
+    // sorted ascending by time, and returns them alongside the time of the last document
+    // returned. Passing that time back in as marker resumes the range just after it, so a
+    // caller can page through a long time range without pulling it all into memory at once.
+    pub(crate) fn query(
+        &self,
+        station_id: &str,
+        start: bson::DateTime,
+        end: bson::DateTime,
+        max_entries: usize,
+        marker: Option<bson::DateTime>,
+    ) -> Result<(Vec<BuoyDatum>, Option<bson::DateTime>), BuoyError> {
+        let mut time_filter = doc! { "$gte": start, "$lte": end };
+        if let Some(marker) = marker {
+            time_filter.insert("$gt", marker);
         }
 
-        let mut chart = Chart::new(120, 60, -100.0, 100.0);
-        chart
-            .linecolorplot(&Shape::Lines(&swh), RGB8 { r: 255, g: 0, b: 0 })
-            .display();
+        let cur = self.coll.find(
+            doc! { "station_id": station_id, "time": time_filter },
+            FindOptions::builder()
+                .sort(doc! { "time": 1 })
+                .limit(max_entries as i64)
+                .build(),
+        )?;
 
-        Ok(())
+        let data: Vec<BuoyDatum> = cur
+            .map(|d| d.map_err(BuoyError::from))
+            .collect::<Result<_, BuoyError>>()?;
+        let next_marker = data.last().map(|d| d.time);
+        Ok((data, next_marker))
     }
 }
 
+// chunk_size_for estimates a good insert_many batch size for a CSV file of the given byte
+// length: large files are split into about available_parallelism * CHUNKS_PER_THREAD chunks,
+// while small files stay in a single batch.
+fn chunk_size_for(file_len: u64) -> usize {
+    let approx_records = (file_len / EST_RECORD_BYTES).max(1) as usize;
+    let parallelism = thread::available_parallelism().map_or(1, |n| n.get());
+    (approx_records / (parallelism * CHUNKS_PER_THREAD)).max(MIN_CHUNK)
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
-    // Create new buoy collection.
-    let buoy_coll = BuoyCollection::new(env::var("DEBUG").is_ok())?;
+    let subcommand = env::args().nth(1);
+
+    // Only the one-shot demo path below wants a freshly dropped-and-recreated collection;
+    // `serve` and `export-influx` read whatever data is already there and must attach to it, so
+    // running them doesn't wipe out everything ingested so far.
+    let fresh = subcommand.is_none();
+    let buoy_coll = BuoyCollection::new(env::var("DEBUG").is_ok(), fresh)?;
+
+    // `serve` turns the crate into a long-running REST API instead of running the one-shot demo
+    // below.
+    if subcommand.as_deref() == Some("serve") {
+        let addr = env::var("ADDR").unwrap_or("0.0.0.0:8080".to_string());
+        return http::serve(buoy_coll, &addr);
+    }
+
+    // `export-influx` writes the collection out in InfluxDB line protocol instead of running
+    // the one-shot demo below.
+    if subcommand.as_deref() == Some("export-influx") {
+        let target = match env::var("INFLUX_URL") {
+            Ok(url) => influx::Target::Influx(url),
+            Err(_) => influx::Target::Stdout,
+        };
+        return influx::export(&buoy_coll, env::var("INFLUX_STATION").ok().as_deref(), target);
+    }
 
     // Populate the collection with data from 2017-short.csv.
-    buoy_coll.load_csv("data/2017-short.csv")?;
+    buoy_coll.load_csv("data/2017-short.csv", RowPolicy::FailFast)?;
 
     // List the buoys available for query.
     buoy_coll.list_buoys()?;
 
-    // Draw the 'Belmullet_Inner' buoy.
-    buoy_coll.draw_buoy("Belmullet_Inner")?;
+    // Render the charts described by the config file, printing to the terminal unless the
+    // config gives an output_dir to save image files into instead.
+    let chart_config = env::var("CHART_CONFIG").unwrap_or("charts.toml".to_string());
+    chart::render_charts(&buoy_coll, &chart_config)?;
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // chunk_size_for_stays_at_min_chunk_for_small_files checks that small inputs get a single
+    // batch at the floor size instead of splitting into many tiny insert_many calls.
+    #[test]
+    fn chunk_size_for_stays_at_min_chunk_for_small_files() {
+        assert_eq!(chunk_size_for(0), MIN_CHUNK);
+        assert_eq!(chunk_size_for(EST_RECORD_BYTES * 10), MIN_CHUNK);
+    }
+
+    // chunk_size_for_splits_large_files_into_many_chunks checks that a large input produces a
+    // chunk size above the floor and is still split into multiple batches.
+    #[test]
+    fn chunk_size_for_splits_large_files_into_many_chunks() {
+        let huge = chunk_size_for(EST_RECORD_BYTES * 10_000_000);
+        assert!(
+            huge > MIN_CHUNK,
+            "expected a huge file to produce a chunk size above MIN_CHUNK, got {}",
+            huge
+        );
+        assert!(
+            10_000_000 / huge > 1,
+            "expected a huge file to be split into multiple batches, got chunk size {}",
+            huge
+        );
+    }
+
+    // query_pagination_round_trips_marker loads a few records for one station and pages through
+    // them two at a time, checking that the marker returned by one page resumes the next page
+    // exactly where it left off.
+    #[test]
+    fn query_pagination_round_trips_marker() {
+        env::set_var("COLLECTION", "test_query_pagination_round_trips_marker");
+        let coll = BuoyCollection::new(false, true).expect("connect to mongodb");
+
+        let station = "TestBuoy";
+        let path = env::temp_dir().join("buoys_query_pagination_round_trips_marker.csv");
+        fs::write(
+            &path,
+            "time,longitude,latitude,station_id,significant_wave_height,mean_wave_period,mean_wave_direction,wave_power,peak_period,energy_period\n\
+             2024-01-01T00:00:00Z,-10.0,54.0,TestBuoy,1.0,5.0,180.0,10.0,8.0,6.0\n\
+             2024-01-01T01:00:00Z,-10.0,54.0,TestBuoy,1.1,5.1,181.0,10.1,8.1,6.1\n\
+             2024-01-01T02:00:00Z,-10.0,54.0,TestBuoy,1.2,5.2,182.0,10.2,8.2,6.2\n",
+        )
+        .expect("write test csv");
+        coll.load_csv(path.to_str().unwrap(), RowPolicy::FailFast)
+            .expect("load test csv");
+
+        let start = bson::DateTime::parse_rfc3339_str("2024-01-01T00:00:00Z").unwrap();
+        let end = bson::DateTime::parse_rfc3339_str("2024-01-01T23:59:59Z").unwrap();
+
+        let (first_page, marker) = coll.query(station, start, end, 2, None).expect("first page");
+        assert_eq!(first_page.len(), 2);
+        let marker = marker.expect("marker after a non-empty page");
+
+        let (second_page, next_marker) = coll
+            .query(station, start, end, 2, Some(marker))
+            .expect("second page");
+        assert_eq!(second_page.len(), 1);
+        assert_eq!(
+            second_page[0].time,
+            bson::DateTime::parse_rfc3339_str("2024-01-01T02:00:00Z").unwrap()
+        );
+        assert_eq!(next_marker, Some(second_page[0].time));
+
+        coll.delete_buoy(station).ok();
+        let _ = fs::remove_file(&path);
+    }
+}